@@ -1,14 +1,57 @@
+use crate::error::MsgpackError;
+use crate::APIMod;
 use chrono::{TimeZone, Utc};
-use pyo3::types::PyDict;
+use pyo3::types::{PyBytes, PyDict};
 use pyo3::{prelude::*, IntoPyObjectExt};
+use std::collections::HashMap;
 use std::io::Read;
 
+/// Options threaded through a single decode call.
+pub struct DecodeOptions<'py> {
+    pub ext_hook: Option<&'py Bound<'py, PyAny>>,
+    pub reconstruct_mods: bool,
+}
+
+impl<'py> DecodeOptions<'py> {
+    pub fn new(ext_hook: Option<&'py Bound<'py, PyAny>>, reconstruct_mods: bool) -> Self {
+        Self {
+            ext_hook,
+            reconstruct_mods,
+        }
+    }
+}
+
+/// What a following array should be interpreted as, established by the
+/// surrounding structure rather than by sniffing the array's contents.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ArrayContext {
+    /// A plain array; decode its elements with no special interpretation.
+    Normal,
+    /// The value of a `mods` map key: each element is a `[acronym, settings]`
+    /// mod entry.
+    Mods,
+    /// One `[acronym, settings]` mod entry.
+    ModEntry,
+    /// A mod's settings, encoded as alternating key/value pairs rather than
+    /// a real msgpack map (matches `write_api_mod`).
+    ModSettings,
+}
+
 pub fn read_object(
     py: Python<'_>,
     cursor: &mut std::io::Cursor<&[u8]>,
-    api_mod: bool,
+    opts: &DecodeOptions,
 ) -> PyResult<PyObject> {
-    match rmp::decode::read_marker(cursor) {
+    read_object_ctx(py, cursor, ArrayContext::Normal, opts)
+}
+
+fn read_object_ctx(
+    py: Python<'_>,
+    cursor: &mut std::io::Cursor<&[u8]>,
+    ctx: ArrayContext,
+    opts: &DecodeOptions,
+) -> PyResult<PyObject> {
+    match rmp::decode::read_marker(cursor).map_err(MsgpackError::from) {
         Ok(marker) => match marker {
             rmp::Marker::Null => Ok(py.None()),
             rmp::Marker::True => Ok(true.into_py_any(py)?),
@@ -105,31 +148,31 @@ pub fn read_object(
                 let len = u32::from_be_bytes(buf);
                 read_string(py, cursor, len)
             }
-            rmp::Marker::FixArray(len) => read_array(py, cursor, len as u32, api_mod),
+            rmp::Marker::FixArray(len) => read_array(py, cursor, len as u32, ctx, opts),
             rmp::Marker::Array16 => {
                 let mut buf = [0u8; 2];
                 cursor.read_exact(&mut buf).map_err(to_py_err)?;
                 let len = u16::from_be_bytes(buf) as u32;
-                read_array(py, cursor, len, api_mod)
+                read_array(py, cursor, len, ctx, opts)
             }
             rmp::Marker::Array32 => {
                 let mut buf = [0u8; 4];
                 cursor.read_exact(&mut buf).map_err(to_py_err)?;
                 let len = u32::from_be_bytes(buf);
-                read_array(py, cursor, len, api_mod)
+                read_array(py, cursor, len, ctx, opts)
             }
-            rmp::Marker::FixMap(len) => read_map(py, cursor, len as u32),
+            rmp::Marker::FixMap(len) => read_map(py, cursor, len as u32, opts),
             rmp::Marker::Map16 => {
                 let mut buf = [0u8; 2];
                 cursor.read_exact(&mut buf).map_err(to_py_err)?;
                 let len = u16::from_be_bytes(buf) as u32;
-                read_map(py, cursor, len)
+                read_map(py, cursor, len, opts)
             }
             rmp::Marker::Map32 => {
                 let mut buf = [0u8; 4];
                 cursor.read_exact(&mut buf).map_err(to_py_err)?;
                 let len = u32::from_be_bytes(buf);
-                read_map(py, cursor, len)
+                read_map(py, cursor, len, opts)
             }
             rmp::Marker::F32 => {
                 let mut buf = [0u8; 4];
@@ -143,37 +186,32 @@ pub fn read_object(
                 let val = f64::from_be_bytes(buf);
                 Ok(val.into_pyobject(py)?.into_any().unbind())
             }
-            rmp::Marker::FixExt1 => read_ext(py, cursor, 1),
-            rmp::Marker::FixExt2 => read_ext(py, cursor, 2),
-            rmp::Marker::FixExt4 => read_ext(py, cursor, 4),
-            rmp::Marker::FixExt8 => read_ext(py, cursor, 8),
-            rmp::Marker::FixExt16 => read_ext(py, cursor, 16),
+            rmp::Marker::FixExt1 => read_ext(py, cursor, 1, opts),
+            rmp::Marker::FixExt2 => read_ext(py, cursor, 2, opts),
+            rmp::Marker::FixExt4 => read_ext(py, cursor, 4, opts),
+            rmp::Marker::FixExt8 => read_ext(py, cursor, 8, opts),
+            rmp::Marker::FixExt16 => read_ext(py, cursor, 16, opts),
             rmp::Marker::Ext8 => {
                 let mut buf = [0u8; 1];
                 cursor.read_exact(&mut buf).map_err(to_py_err)?;
                 let len = buf[0] as u32;
-                read_ext(py, cursor, len)
+                read_ext(py, cursor, len, opts)
             }
             rmp::Marker::Ext16 => {
                 let mut buf = [0u8; 2];
                 cursor.read_exact(&mut buf).map_err(to_py_err)?;
                 let len = u16::from_be_bytes(buf) as u32;
-                read_ext(py, cursor, len)
+                read_ext(py, cursor, len, opts)
             }
             rmp::Marker::Ext32 => {
                 let mut buf = [0u8; 4];
                 cursor.read_exact(&mut buf).map_err(to_py_err)?;
                 let len = u32::from_be_bytes(buf);
-                read_ext(py, cursor, len)
+                read_ext(py, cursor, len, opts)
             }
-            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
-                "Unsupported MessagePack marker",
-            )),
+            other => Err(MsgpackError::UnsupportedType(format!("{:?}", other)).into()),
         },
-        Err(e) => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-            "Failed to read marker: {:?}",
-            e
-        ))),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -184,8 +222,7 @@ fn read_string(
 ) -> PyResult<PyObject> {
     let mut buf = vec![0u8; len as usize];
     cursor.read_exact(&mut buf).map_err(to_py_err)?;
-    let s = String::from_utf8(buf)
-        .map_err(|_| PyErr::new::<pyo3::exceptions::PyUnicodeDecodeError, _>("Invalid UTF-8"))?;
+    let s = String::from_utf8(buf).map_err(|_| MsgpackError::InvalidUtf8)?;
     Ok(s.into_pyobject(py)?.into_any().unbind())
 }
 
@@ -193,68 +230,121 @@ fn read_array(
     py: Python,
     cursor: &mut std::io::Cursor<&[u8]>,
     len: u32,
-    api_mod: bool,
+    ctx: ArrayContext,
+    opts: &DecodeOptions,
 ) -> PyResult<PyObject> {
-    let mut items = Vec::new();
-    let array_len = if api_mod { len * 2 } else { len };
-    let dict = PyDict::new(py);
-    let mut i = 0;
-    if len == 2 && !api_mod {
-        // 姑且这样判断：列表长度为2，第一个元素为长度为2的字符串，api_mod 模式未启用（不存在嵌套 APIMod）
-        let obj1 = read_object(py, cursor, false)?;
-        if obj1.extract::<String>(py).map_or(false, |k| k.len() == 2) {
-            let obj2 = read_object(py, cursor, true)?;
-
+    match ctx {
+        ArrayContext::Mods => {
+            let mut mods = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                mods.push(read_object_ctx(py, cursor, ArrayContext::ModEntry, opts)?);
+            }
+            Ok(mods.into_pyobject(py)?.into_any().unbind())
+        }
+        ArrayContext::ModEntry if len == 2 => {
+            let acronym = read_object_ctx(py, cursor, ArrayContext::Normal, opts)?;
+            let settings = read_object_ctx(py, cursor, ArrayContext::ModSettings, opts)?;
+            if opts.reconstruct_mods {
+                if let Some(api_mod) = build_api_mod(py, &acronym, &settings)? {
+                    return Ok(api_mod);
+                }
+            }
+            // Not reconstructing, or the entry wasn't a well-formed
+            // [acronym, settings] pair - return the plain decoded value
+            // rather than erroring, same as the pre-reconstruction behavior.
             let api_mod_dict = PyDict::new(py);
-            api_mod_dict.set_item("acronym", obj1)?;
-            api_mod_dict.set_item("settings", obj2)?;
-
-            return Ok(api_mod_dict.into_pyobject(py)?.into_any().unbind());
-        } else {
-            items.push(obj1);
-            i += 1;
+            api_mod_dict.set_item("acronym", acronym)?;
+            api_mod_dict.set_item("settings", settings)?;
+            Ok(api_mod_dict.into_pyobject(py)?.into_any().unbind())
         }
-    }
-    while i < array_len {
-        if api_mod && i % 2 == 0 {
-            let key = read_object(py, cursor, false)?;
-            let value = read_object(py, cursor, false)?;
-            dict.set_item(key, value)?;
-            i += 2;
-        } else {
-            let item = read_object(py, cursor, api_mod)?;
-            items.push(item);
-            i += 1;
+        ArrayContext::ModSettings => {
+            let dict = PyDict::new(py);
+            for _ in 0..len {
+                let key = read_object_ctx(py, cursor, ArrayContext::Normal, opts)?;
+                let value = read_object_ctx(py, cursor, ArrayContext::Normal, opts)?;
+                dict.set_item(key, value)?;
+            }
+            Ok(dict.into_pyobject(py)?.into_any().unbind())
+        }
+        // A mod entry is always a 2-element [acronym, settings] pair; a
+        // different length here means the `mods` value wasn't shaped like
+        // one, so fall back to decoding it as a plain array.
+        ArrayContext::Normal | ArrayContext::ModEntry => {
+            let mut items = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                items.push(read_object_ctx(py, cursor, ArrayContext::Normal, opts)?);
+            }
+            Ok(items.into_pyobject(py)?.into_any().unbind())
         }
-    }
-
-    if api_mod {
-        return Ok(dict.into_pyobject(py)?.into_any().unbind());
-    } else {
-        Ok(items.into_pyobject(py)?.into_any().unbind())
     }
 }
 
-fn read_map(py: Python, cursor: &mut std::io::Cursor<&[u8]>, len: u32) -> PyResult<PyObject> {
-    let mut pairs = Vec::new();
+fn read_map(
+    py: Python,
+    cursor: &mut std::io::Cursor<&[u8]>,
+    len: u32,
+    opts: &DecodeOptions,
+) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
     for _ in 0..len {
-        let key = read_object(py, cursor, false)?;
-        let value = read_object(py, cursor, false)?;
-        pairs.push((key, value));
+        let key = read_object_ctx(py, cursor, ArrayContext::Normal, opts)?;
+        let value_ctx = if is_mods_key(py, &key) {
+            ArrayContext::Mods
+        } else {
+            ArrayContext::Normal
+        };
+        let value = read_object_ctx(py, cursor, value_ctx, opts)?;
+        dict.set_item(key, value)?;
     }
+    Ok(dict.into_pyobject(py)?.into_any().unbind())
+}
 
-    let dict = PyDict::new(py);
-    for (key, value) in pairs {
-        dict.set_item(key, value)?;
+fn is_mods_key(py: Python, key: &PyObject) -> bool {
+    key.extract::<String>(py).map_or(false, |k| k == "mods")
+}
+
+/// Attempts to reconstruct a `[acronym, settings]` mod entry as an
+/// `APIMod`. Returns `Ok(None)` - rather than erroring - when the entry
+/// doesn't actually have that shape, so a document that merely has a
+/// `mods` key with unexpected contents still decodes instead of failing.
+fn build_api_mod(
+    py: Python,
+    acronym: &PyObject,
+    settings: &PyObject,
+) -> PyResult<Option<PyObject>> {
+    let Ok(acronym) = acronym.extract::<String>(py) else {
+        return Ok(None);
+    };
+    let Ok(settings_dict) = settings.bind(py).downcast::<PyDict>() else {
+        return Ok(None);
+    };
+    let mut settings_map = HashMap::with_capacity(settings_dict.len());
+    for (key, value) in settings_dict.iter() {
+        let Ok(key) = key.extract::<String>() else {
+            return Ok(None);
+        };
+        settings_map.insert(key, value.unbind());
     }
-    return Ok(dict.into_pyobject(py)?.into_any().unbind());
+    let api_mod = Py::new(
+        py,
+        APIMod {
+            acronym,
+            settings: settings_map,
+        },
+    )?;
+    Ok(Some(api_mod.into_any()))
 }
 
 fn to_py_err(err: std::io::Error) -> PyErr {
-    PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("IO error: {}", err))
+    MsgpackError::Io(err).into()
 }
 
-fn read_ext(py: Python, cursor: &mut std::io::Cursor<&[u8]>, len: u32) -> PyResult<PyObject> {
+fn read_ext(
+    py: Python,
+    cursor: &mut std::io::Cursor<&[u8]>,
+    len: u32,
+    opts: &DecodeOptions,
+) -> PyResult<PyObject> {
     // Read the extension type
     let mut type_buf = [0u8; 1];
     cursor.read_exact(&mut type_buf).map_err(to_py_err)?;
@@ -267,12 +357,13 @@ fn read_ext(py: Python, cursor: &mut std::io::Cursor<&[u8]>, len: u32) -> PyResu
     // Handle timestamp extension (type = -1)
     if ext_type == -1 {
         read_timestamp(py, &data)
+    } else if let Some(hook) = opts.ext_hook {
+        // Hand any other extension type to the caller-supplied hook, with
+        // the payload as `bytes`.
+        let data = PyBytes::new(py, &data);
+        Ok(hook.call1((ext_type, data))?.unbind())
     } else {
-        // For other extension types, return as bytes or handle as needed
-        Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-            "Unsupported extension type: {}",
-            ext_type
-        )))
+        Err(MsgpackError::WrongExtForTimestamp(ext_type).into())
     }
 }
 
@@ -301,12 +392,130 @@ fn read_timestamp(py: Python, data: &[u8]) -> PyResult<PyObject> {
             (secs, nsec)
         }
         _ => {
-            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                "Invalid timestamp data length: {}",
-                data.len()
-            )));
+            return Err(MsgpackError::WrongLenForTimestamp(data.len()).into());
         }
     };
     let time = Utc.timestamp_opt(secs as i64, nsec).single();
     Ok(time.into_pyobject(py)?.into_any().unbind())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::write_object;
+    use crate::APIMod;
+    use pyo3::types::PyList;
+    use std::io::Cursor;
+
+    fn roundtrip(py: Python<'_>, obj: &Bound<'_, PyAny>, reconstruct_mods: bool) -> PyObject {
+        let mut buf = Vec::new();
+        write_object(&mut buf, obj, None).expect("encode should succeed");
+        let mut cursor = Cursor::new(buf.as_slice());
+        let opts = DecodeOptions::new(None, reconstruct_mods);
+        read_object(py, &mut cursor, &opts).expect("decode should succeed")
+    }
+
+    #[test]
+    fn reconstructs_api_mod_under_mods_key() {
+        Python::with_gil(|py| {
+            let mut settings = HashMap::new();
+            settings.insert(
+                "speed_change".to_string(),
+                1.5f64.into_pyobject(py).unwrap().into_any().unbind(),
+            );
+            let api_mod = Py::new(
+                py,
+                APIMod {
+                    acronym: "DT".to_string(),
+                    settings,
+                },
+            )
+            .unwrap();
+
+            let mods = PyList::new(py, [api_mod.into_any()]).unwrap();
+            let dict = PyDict::new(py);
+            dict.set_item("mods", mods).unwrap();
+
+            let decoded = roundtrip(py, &dict.into_any(), true);
+            let decoded_dict = decoded.bind(py).downcast::<PyDict>().unwrap();
+            let decoded_mods = decoded_dict
+                .get_item("mods")
+                .unwrap()
+                .unwrap()
+                .downcast::<PyList>()
+                .unwrap()
+                .clone();
+            let first = decoded_mods.get_item(0).unwrap();
+            let api_mod_ref = first
+                .downcast::<APIMod>()
+                .expect("should be an APIMod instance, not a dict");
+            assert_eq!(api_mod_ref.borrow().acronym, "DT");
+        });
+    }
+
+    #[test]
+    fn plain_two_element_list_outside_mods_key_is_not_misclassified() {
+        Python::with_gil(|py| {
+            // A 2-element array starting with a 2-char string looks just
+            // like a mod entry structurally, but it isn't reached through a
+            // "mods" key here, so it must decode as an ordinary list.
+            let list = PyList::new(py, ["DT", "ignored"]).unwrap();
+            let decoded = roundtrip(py, &list.into_any(), true);
+            let decoded_list = decoded
+                .bind(py)
+                .downcast::<PyList>()
+                .expect("should decode as a plain list, not a mod entry");
+            assert_eq!(decoded_list.len(), 2);
+            assert_eq!(
+                decoded_list.get_item(0).unwrap().extract::<String>().unwrap(),
+                "DT"
+            );
+        });
+    }
+
+    #[test]
+    fn api_mod_reconstruction_falls_back_when_mods_entry_is_malformed() {
+        Python::with_gil(|py| {
+            // A "mods" key whose entry isn't a real [acronym, settings] pair
+            // (wrong types, not a settings dict) must still decode instead
+            // of hard-erroring.
+            let entry = PyList::new(
+                py,
+                [
+                    123i32.into_pyobject(py).unwrap().into_any(),
+                    "notadict".into_pyobject(py).unwrap().into_any(),
+                ],
+            )
+            .unwrap();
+            let mods = PyList::new(py, [entry.into_any()]).unwrap();
+            let dict = PyDict::new(py);
+            dict.set_item("mods", mods).unwrap();
+
+            let decoded = roundtrip(py, &dict.into_any(), true);
+            let decoded_dict = decoded.bind(py).downcast::<PyDict>().unwrap();
+            let decoded_mods = decoded_dict
+                .get_item("mods")
+                .unwrap()
+                .unwrap()
+                .downcast::<PyList>()
+                .unwrap()
+                .clone();
+            assert_eq!(decoded_mods.len(), 1);
+            let fallback = decoded_mods
+                .get_item(0)
+                .unwrap()
+                .downcast::<PyDict>()
+                .expect("malformed entry should fall back to a plain dict")
+                .clone();
+            assert_eq!(
+                fallback
+                    .get_item("acronym")
+                    .unwrap()
+                    .unwrap()
+                    .extract::<i32>()
+                    .unwrap(),
+                123
+            );
+        });
+    }
+}
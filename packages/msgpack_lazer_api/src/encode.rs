@@ -1,92 +1,133 @@
-use crate::APIMod;
+use crate::error::MsgpackError;
+use crate::{APIMod, ExtType};
 use chrono::{DateTime, Utc};
 use pyo3::prelude::{PyAnyMethods, PyDictMethods, PyListMethods, PyStringMethods};
 use pyo3::types::{PyBool, PyBytes, PyDateTime, PyDict, PyFloat, PyInt, PyList, PyNone, PyString};
 use pyo3::{Bound, PyAny, PyRef, Python};
 use std::io::Write;
 
-fn write_list(buf: &mut Vec<u8>, obj: &Bound<'_, PyList>) {
-    rmp::encode::write_array_len(buf, obj.len() as u32).unwrap();
+/// Caps how many times the `default` hook may be chained for a single
+/// value before giving up, so a hook that keeps returning an
+/// unsupported type can't recurse forever.
+const MAX_DEFAULT_DEPTH: u32 = 5;
+
+type DefaultHook<'py> = Option<&'py Bound<'py, PyAny>>;
+
+fn write_list(
+    buf: &mut Vec<u8>,
+    obj: &Bound<'_, PyList>,
+    default: DefaultHook,
+) -> Result<(), MsgpackError> {
+    rmp::encode::write_array_len(buf, obj.len() as u32)?;
     for item in obj.iter() {
-        write_object(buf, &item);
+        write_object(buf, &item, default)?;
     }
+    Ok(())
 }
 
-fn write_string(buf: &mut Vec<u8>, obj: &Bound<'_, PyString>) {
+fn write_string(buf: &mut Vec<u8>, obj: &Bound<'_, PyString>) -> Result<(), MsgpackError> {
     let s = obj.to_string_lossy();
-    rmp::encode::write_str(buf, &s).unwrap();
+    rmp::encode::write_str(buf, &s)?;
+    Ok(())
 }
 
-fn write_integer(buf: &mut Vec<u8>, obj: &Bound<'_, PyInt>) {
-    if let Ok(val) = obj.extract::<i32>() {
-        rmp::encode::write_i32(buf, val).unwrap();
-    } else if let Ok(val) = obj.extract::<i64>() {
-        rmp::encode::write_i64(buf, val).unwrap();
+fn write_integer(buf: &mut Vec<u8>, obj: &Bound<'_, PyInt>) -> Result<(), MsgpackError> {
+    if let Ok(val) = obj.extract::<i64>() {
+        rmp::encode::write_sint(buf, val)?;
+    } else if let Ok(val) = obj.extract::<u64>() {
+        // Beyond i64::MAX: only representable as an unsigned msgpack int
+        // (e.g. u64 ids/bitflags the decoder already reads via Marker::U64).
+        rmp::encode::write_uint(buf, val)?;
     } else {
-        panic!("Unsupported integer type");
+        // Outside u64 range entirely (or too negative for i64) - msgpack
+        // has no wider integer type to fall back to.
+        return Err(MsgpackError::IntegerOverflow);
     }
+    Ok(())
 }
 
-fn write_float(buf: &mut Vec<u8>, obj: &Bound<'_, PyAny>) {
+fn write_float(buf: &mut Vec<u8>, obj: &Bound<'_, PyAny>) -> Result<(), MsgpackError> {
     if let Ok(val) = obj.extract::<f32>() {
-        rmp::encode::write_f32(buf, val).unwrap();
+        rmp::encode::write_f32(buf, val)?;
     } else if let Ok(val) = obj.extract::<f64>() {
-        rmp::encode::write_f64(buf, val).unwrap();
+        rmp::encode::write_f64(buf, val)?;
     } else {
-        panic!("Unsupported float type");
+        return Err(MsgpackError::UnsupportedType("float".to_string()));
     }
+    Ok(())
 }
 
-fn write_bool(buf: &mut Vec<u8>, obj: &Bound<'_, PyBool>) {
+fn write_bool(buf: &mut Vec<u8>, obj: &Bound<'_, PyBool>) -> Result<(), MsgpackError> {
     if let Ok(b) = obj.extract::<bool>() {
-        rmp::encode::write_bool(buf, b).unwrap();
+        rmp::encode::write_bool(buf, b)?;
     } else {
-        panic!("Unsupported boolean type");
+        return Err(MsgpackError::UnsupportedType("boolean".to_string()));
     }
+    Ok(())
 }
 
-fn write_bin(buf: &mut Vec<u8>, obj: &Bound<'_, PyBytes>) {
+fn write_bin(buf: &mut Vec<u8>, obj: &Bound<'_, PyBytes>) -> Result<(), MsgpackError> {
     if let Ok(bytes) = obj.extract::<Vec<u8>>() {
-        rmp::encode::write_bin(buf, &bytes).unwrap();
+        rmp::encode::write_bin(buf, &bytes)?;
     } else {
-        panic!("Unsupported binary type");
+        return Err(MsgpackError::UnsupportedType("binary".to_string()));
     }
+    Ok(())
 }
 
-fn write_hashmap(buf: &mut Vec<u8>, obj: &Bound<'_, PyDict>) {
-    rmp::encode::write_map_len(buf, obj.len() as u32).unwrap();
+fn write_hashmap(
+    buf: &mut Vec<u8>,
+    obj: &Bound<'_, PyDict>,
+    default: DefaultHook,
+) -> Result<(), MsgpackError> {
+    rmp::encode::write_map_len(buf, obj.len() as u32)?;
     for (key, value) in obj.iter() {
-        write_object(buf, &key);
-        write_object(buf, &value);
+        write_object(buf, &key, default)?;
+        write_object(buf, &value, default)?;
     }
+    Ok(())
 }
 
-fn write_nil(buf: &mut Vec<u8>){
-    rmp::encode::write_nil(buf).unwrap();
+fn write_nil(buf: &mut Vec<u8>) -> Result<(), MsgpackError> {
+    rmp::encode::write_nil(buf)?;
+    Ok(())
 }
 
 // https://github.com/ppy/osu/blob/3dced3/osu.Game/Online/API/ModSettingsDictionaryFormatter.cs
-fn write_api_mod(buf: &mut Vec<u8>, api_mod: PyRef<APIMod>) {
-    rmp::encode::write_array_len(buf, 2).unwrap();
-    rmp::encode::write_str(buf, &api_mod.acronym).unwrap();
-    rmp::encode::write_array_len(buf, api_mod.settings.len() as u32).unwrap();
+fn write_api_mod(
+    buf: &mut Vec<u8>,
+    api_mod: PyRef<APIMod>,
+    default: DefaultHook,
+) -> Result<(), MsgpackError> {
+    rmp::encode::write_array_len(buf, 2)?;
+    rmp::encode::write_str(buf, &api_mod.acronym)?;
+    rmp::encode::write_array_len(buf, api_mod.settings.len() as u32)?;
     for (k, v) in api_mod.settings.iter() {
-        rmp::encode::write_str(buf, k).unwrap();
-        Python::with_gil(|py| write_object(buf, &v.bind(py)));
+        rmp::encode::write_str(buf, k)?;
+        Python::with_gil(|py| write_object(buf, &v.bind(py), default))?;
     }
+    Ok(())
+}
+
+fn write_ext_type(buf: &mut Vec<u8>, ext: &ExtType) -> Result<(), MsgpackError> {
+    rmp::encode::write_ext_meta(buf, ext.data.len() as u32, ext.code)?;
+    buf.write_all(&ext.data).map_err(MsgpackError::Io)?;
+    Ok(())
 }
 
-fn write_datetime(buf: &mut Vec<u8>, obj: &Bound<'_, PyDateTime>) {
+fn write_datetime(buf: &mut Vec<u8>, obj: &Bound<'_, PyDateTime>) -> Result<(), MsgpackError> {
     if let Ok(dt) = obj.extract::<DateTime<Utc>>() {
         let secs = dt.timestamp();
         let nsec = dt.timestamp_subsec_nanos();
-        write_timestamp(buf, secs, nsec);
+        write_timestamp(buf, secs, nsec)
     } else {
-        panic!("Unsupported datetime type. Check your input, timezone is needed.");
+        Err(MsgpackError::UnsupportedType(
+            "datetime (timezone is needed)".to_string(),
+        ))
     }
 }
 
-fn write_timestamp(wr: &mut Vec<u8>, secs: i64, nsec: u32) {
+fn write_timestamp(wr: &mut Vec<u8>, secs: i64, nsec: u32) -> Result<(), MsgpackError> {
     let buf: Vec<u8> = if nsec == 0 && secs >= 0 && secs <= u32::MAX as i64 {
         // timestamp32: 4-byte big endian seconds
         secs.to_be_bytes()[4..].to_vec()
@@ -101,32 +142,202 @@ fn write_timestamp(wr: &mut Vec<u8>, secs: i64, nsec: u32) {
         v.extend_from_slice(&secs.to_be_bytes());
         v
     };
-    rmp::encode::write_ext_meta(wr, buf.len() as u32, -1).unwrap();
-    wr.write_all(&buf).unwrap();
+    rmp::encode::write_ext_meta(wr, buf.len() as u32, -1)?;
+    wr.write_all(&buf).map_err(MsgpackError::Io)?;
+    Ok(())
 }
 
-pub fn write_object(buf: &mut Vec<u8>, obj: &Bound<'_, PyAny>) {
+/// Encodes `obj` into `buf`. When `default` is given, it is invoked with
+/// any value of a type this encoder doesn't otherwise understand, and its
+/// return value is encoded in place of the original object.
+pub fn write_object(
+    buf: &mut Vec<u8>,
+    obj: &Bound<'_, PyAny>,
+    default: DefaultHook,
+) -> Result<(), MsgpackError> {
+    write_object_with_depth(buf, obj, default, 0)
+}
+
+fn write_object_with_depth(
+    buf: &mut Vec<u8>,
+    obj: &Bound<'_, PyAny>,
+    default: DefaultHook,
+    depth: u32,
+) -> Result<(), MsgpackError> {
     if let Ok(list) = obj.downcast::<PyList>() {
-        write_list(buf, list);
+        write_list(buf, list, default)
     } else if let Ok(string) = obj.downcast::<PyString>() {
-        write_string(buf, string);
+        write_string(buf, string)
     } else if let Ok(integer) = obj.downcast::<PyInt>() {
-        write_integer(buf, integer);
+        write_integer(buf, integer)
     } else if let Ok(float) = obj.downcast::<PyFloat>() {
-        write_float(buf, float);
+        write_float(buf, float)
     } else if let Ok(boolean) = obj.downcast::<PyBool>() {
-        write_bool(buf, boolean);
+        write_bool(buf, boolean)
     } else if let Ok(bytes) = obj.downcast::<PyBytes>() {
-        write_bin(buf, bytes);
+        write_bin(buf, bytes)
     } else if let Ok(dict) = obj.downcast::<PyDict>() {
-        write_hashmap(buf, dict);
+        write_hashmap(buf, dict, default)
     } else if let Ok(_none) = obj.downcast::<PyNone>() {
-        write_nil(buf);
+        write_nil(buf)
     } else if let Ok(datetime) = obj.downcast::<PyDateTime>() {
-        write_datetime(buf, datetime);
+        write_datetime(buf, datetime)
     } else if let Ok(api_mod) = obj.extract::<PyRef<APIMod>>() {
-        write_api_mod(buf, api_mod);
+        write_api_mod(buf, api_mod, default)
+    } else if let Ok(ext) = obj.extract::<PyRef<ExtType>>() {
+        write_ext_type(buf, &ext)
+    } else if let Some(default_fn) = default {
+        if depth >= MAX_DEFAULT_DEPTH {
+            return Err(MsgpackError::UnsupportedType(format!(
+                "`default` did not return a serializable value after {} attempts",
+                MAX_DEFAULT_DEPTH
+            )));
+        }
+        let replaced = default_fn.call1((obj,)).map_err(MsgpackError::from)?;
+        write_object_with_depth(buf, &replaced, default, depth + 1)
     } else {
-        panic!("Unsupported type");
+        let type_name = obj
+            .get_type()
+            .name()
+            .map(|n| n.to_string())
+            .unwrap_or_else(|_| "<unknown>".to_string());
+        Err(MsgpackError::UnsupportedType(type_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{read_object, DecodeOptions};
+    use std::io::Cursor;
+
+    fn roundtrip(py: Python<'_>, obj: &Bound<'_, PyAny>) -> PyObject {
+        let mut buf = Vec::new();
+        write_object(&mut buf, obj, None).expect("encode should succeed");
+        let mut cursor = Cursor::new(buf.as_slice());
+        let opts = DecodeOptions::new(None, true);
+        read_object(py, &mut cursor, &opts).expect("decode should succeed")
+    }
+
+    #[test]
+    fn round_trips_i64_max() {
+        Python::with_gil(|py| {
+            let value = i64::MAX.into_pyobject(py).unwrap().into_any();
+            let decoded = roundtrip(py, &value);
+            assert_eq!(decoded.extract::<i64>(py).unwrap(), i64::MAX);
+        });
+    }
+
+    #[test]
+    fn round_trips_i64_max_plus_one_as_u64() {
+        Python::with_gil(|py| {
+            let raw = i64::MAX as u64 + 1;
+            let value = raw.into_pyobject(py).unwrap().into_any();
+            let decoded = roundtrip(py, &value);
+            assert_eq!(decoded.extract::<u64>(py).unwrap(), raw);
+        });
+    }
+
+    #[test]
+    fn round_trips_u64_max() {
+        Python::with_gil(|py| {
+            let value = u64::MAX.into_pyobject(py).unwrap().into_any();
+            let decoded = roundtrip(py, &value);
+            assert_eq!(decoded.extract::<u64>(py).unwrap(), u64::MAX);
+        });
+    }
+
+    #[test]
+    fn rejects_beyond_u64_max_with_overflow_error() {
+        Python::with_gil(|py| {
+            let raw: u128 = u64::MAX as u128 + 1;
+            let value = raw.into_pyobject(py).unwrap().into_any();
+            let err = crate::encode_py(&value, None).expect_err("should overflow");
+            assert!(err.is_instance_of::<pyo3::exceptions::PyOverflowError>(py));
+        });
+    }
+
+    #[test]
+    fn round_trips_custom_type_via_default_and_ext_hook() {
+        use crate::ExtType;
+        use pyo3::types::{PyCFunction, PySet, PyTuple};
+
+        Python::with_gil(|py| {
+            // `default` boxes an otherwise-unsupported type (a Python `set`
+            // has no msgpack representation) into an `ExtType`; `ext_hook`
+            // unboxes the payload back into a value on decode.
+            let default = PyCFunction::new_closure(
+                py,
+                None,
+                None,
+                |args: &Bound<'_, PyTuple>, _kwargs: Option<&Bound<'_, PyDict>>| -> PyResult<PyObject> {
+                    let py = args.py();
+                    let ext = Py::new(
+                        py,
+                        ExtType {
+                            code: 7,
+                            data: b"boxed-value".to_vec(),
+                        },
+                    )?;
+                    Ok(ext.into_any().unbind())
+                },
+            )
+            .unwrap();
+
+            let ext_hook = PyCFunction::new_closure(
+                py,
+                None,
+                None,
+                |args: &Bound<'_, PyTuple>, _kwargs: Option<&Bound<'_, PyDict>>| -> PyResult<PyObject> {
+                    let py = args.py();
+                    let data: Vec<u8> = args.get_item(1)?.extract()?;
+                    Ok(data.into_pyobject(py)?.into_any().unbind())
+                },
+            )
+            .unwrap();
+
+            let set = PySet::empty(py).unwrap().into_any();
+            let mut buf = Vec::new();
+            write_object(&mut buf, &set, Some(default.as_any()))
+                .expect("encode via default should succeed");
+
+            let mut cursor = Cursor::new(buf.as_slice());
+            let opts = DecodeOptions::new(Some(ext_hook.as_any()), true);
+            let decoded =
+                read_object(py, &mut cursor, &opts).expect("decode via ext_hook should succeed");
+            assert_eq!(decoded.extract::<Vec<u8>>(py).unwrap(), b"boxed-value");
+        });
+    }
+
+    #[test]
+    fn default_hook_that_never_resolves_hits_depth_cap() {
+        use pyo3::types::{PyCFunction, PySet, PyTuple};
+
+        Python::with_gil(|py| {
+            // Always returns another unsupported `set`, so `default` keeps
+            // getting re-invoked until the depth cap gives up instead of
+            // recursing forever.
+            let default = PyCFunction::new_closure(
+                py,
+                None,
+                None,
+                |args: &Bound<'_, PyTuple>, _kwargs: Option<&Bound<'_, PyDict>>| -> PyResult<PyObject> {
+                    let py = args.py();
+                    Ok(PySet::empty(py)?.into_any().unbind())
+                },
+            )
+            .unwrap();
+
+            let set = PySet::empty(py).unwrap().into_any();
+            let mut buf = Vec::new();
+            let err = write_object(&mut buf, &set, Some(default.as_any()))
+                .expect_err("should hit the depth cap instead of looping");
+            match err {
+                MsgpackError::UnsupportedType(msg) => {
+                    assert!(msg.contains("default"));
+                }
+                other => panic!("expected UnsupportedType, got {other:?}"),
+            }
+        });
     }
 }
@@ -0,0 +1,80 @@
+use pyo3::exceptions::{PyIOError, PyOverflowError, PyValueError};
+use pyo3::PyErr;
+use std::fmt;
+
+/// Internal error type shared by the encoder and decoder so that failures
+/// surface as catchable Python exceptions instead of panicking across the
+/// FFI boundary.
+#[derive(Debug)]
+pub enum MsgpackError {
+    Io(std::io::Error),
+    UnsupportedType(String),
+    ValueWrite(rmp::encode::ValueWriteError),
+    WrongExtForTimestamp(i8),
+    WrongLenForTimestamp(usize),
+    InvalidUtf8,
+    IntegerOverflow,
+    /// An `ext_hook`/`default` callback raised a Python exception; it is
+    /// carried through as-is rather than mapped to a generic error.
+    Python(PyErr),
+}
+
+impl fmt::Display for MsgpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MsgpackError::Io(e) => write!(f, "IO error: {e}"),
+            MsgpackError::UnsupportedType(ty) => write!(f, "Unsupported type: {ty}"),
+            MsgpackError::ValueWrite(e) => write!(f, "Failed to write MessagePack value: {e}"),
+            MsgpackError::WrongExtForTimestamp(ext) => {
+                write!(f, "Unsupported extension type for timestamp: {ext}")
+            }
+            MsgpackError::WrongLenForTimestamp(len) => {
+                write!(f, "Invalid timestamp data length: {len}")
+            }
+            MsgpackError::InvalidUtf8 => write!(f, "Invalid UTF-8 in string"),
+            MsgpackError::IntegerOverflow => write!(f, "Integer value out of range"),
+            MsgpackError::Python(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for MsgpackError {}
+
+impl From<std::io::Error> for MsgpackError {
+    fn from(e: std::io::Error) -> Self {
+        MsgpackError::Io(e)
+    }
+}
+
+impl From<rmp::encode::ValueWriteError> for MsgpackError {
+    fn from(e: rmp::encode::ValueWriteError) -> Self {
+        MsgpackError::ValueWrite(e)
+    }
+}
+
+impl From<rmp::decode::MarkerReadError> for MsgpackError {
+    fn from(e: rmp::decode::MarkerReadError) -> Self {
+        MsgpackError::Io(e.0)
+    }
+}
+
+impl From<PyErr> for MsgpackError {
+    fn from(e: PyErr) -> Self {
+        MsgpackError::Python(e)
+    }
+}
+
+impl From<MsgpackError> for PyErr {
+    fn from(err: MsgpackError) -> Self {
+        match err {
+            MsgpackError::Python(e) => e,
+            MsgpackError::Io(_) => PyIOError::new_err(err.to_string()),
+            MsgpackError::IntegerOverflow => PyOverflowError::new_err(err.to_string()),
+            MsgpackError::UnsupportedType(_)
+            | MsgpackError::ValueWrite(_)
+            | MsgpackError::WrongExtForTimestamp(_)
+            | MsgpackError::WrongLenForTimestamp(_)
+            | MsgpackError::InvalidUtf8 => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
@@ -1,25 +1,79 @@
 mod decode;
 mod encode;
+mod error;
+mod unpacker;
 
 use pyo3::prelude::*;
+use std::collections::HashMap;
+use unpacker::Unpacker;
+
+/// A raw MessagePack extension payload, used to round-trip application
+/// types that have no direct msgpack representation: `encode`'s `default`
+/// hook can return one to emit a custom ext type, and `decode`'s `ext_hook`
+/// receives one's `code`/`data` for any ext type other than timestamps.
+#[pyclass]
+struct ExtType {
+    #[pyo3(get, set)]
+    code: i8,
+    #[pyo3(get, set)]
+    data: Vec<u8>,
+}
+
+#[pymethods]
+impl ExtType {
+    #[new]
+    fn new(code: i8, data: Vec<u8>) -> Self {
+        Self { code, data }
+    }
+}
+
+/// An osu! API mod: an acronym (e.g. "DT") paired with its settings.
+/// With `reconstruct_mods` set (the default), `decode` reconstructs this from
+/// a `[acronym, settings]` entry in an array under a `mods` key (see
+/// `decode::build_api_mod`) instead of returning a plain dict, and `encode`
+/// writes it back out in the same wire shape (see `encode::write_api_mod`).
+#[pyclass]
+struct APIMod {
+    #[pyo3(get, set)]
+    acronym: String,
+    #[pyo3(get, set)]
+    settings: HashMap<String, Py<PyAny>>,
+}
+
+#[pymethods]
+impl APIMod {
+    #[new]
+    fn new(acronym: String, settings: HashMap<String, Py<PyAny>>) -> Self {
+        Self { acronym, settings }
+    }
+}
 
 #[pyfunction]
-#[pyo3(name = "encode")]
-fn encode_py(obj: &Bound<'_, PyAny>) -> PyResult<Vec<u8>> {
+#[pyo3(name = "encode", signature = (obj, default=None))]
+fn encode_py(obj: &Bound<'_, PyAny>, default: Option<Bound<'_, PyAny>>) -> PyResult<Vec<u8>> {
     let mut buf = Vec::new();
-    encode::write_object(&mut buf, obj);
+    encode::write_object(&mut buf, obj, default.as_ref())?;
     Ok(buf)
 }
 
 #[pyfunction]
-#[pyo3(name = "decode")]
-fn decode_py(py: Python, data: &[u8]) -> PyResult<PyObject> {
+#[pyo3(name = "decode", signature = (data, ext_hook=None, reconstruct_mods=true))]
+fn decode_py(
+    py: Python,
+    data: &[u8],
+    ext_hook: Option<Bound<'_, PyAny>>,
+    reconstruct_mods: bool,
+) -> PyResult<PyObject> {
     let mut cursor = std::io::Cursor::new(data);
-    decode::read_object(py, &mut cursor, false)
+    let opts = decode::DecodeOptions::new(ext_hook.as_ref(), reconstruct_mods);
+    decode::read_object(py, &mut cursor, &opts)
 }
 
 #[pymodule]
 fn msgpack_lazer_api(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<ExtType>()?;
+    m.add_class::<APIMod>()?;
+    m.add_class::<Unpacker>()?;
     m.add_function(wrap_pyfunction!(encode_py, m)?)?;
     m.add_function(wrap_pyfunction!(decode_py, m)?)?;
     Ok(())
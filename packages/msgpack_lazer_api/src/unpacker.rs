@@ -0,0 +1,160 @@
+use crate::decode;
+use pyo3::exceptions::PyIOError;
+use pyo3::prelude::*;
+use std::io::Cursor;
+
+/// Incrementally decodes a stream of concatenated MessagePack documents.
+///
+/// Unlike `decode`, which expects its input to hold exactly one object,
+/// `Unpacker` lets callers `feed` bytes as they arrive (e.g. off a socket)
+/// and iterate over however many complete objects are currently buffered,
+/// keeping the unconsumed tail around for the next `feed`.
+#[pyclass]
+pub struct Unpacker {
+    buffer: Vec<u8>,
+    /// Offset into `buffer` consumed so far; reset to 0 whenever `compact`
+    /// drops the already-read prefix.
+    pos: usize,
+    /// Total bytes consumed over the Unpacker's lifetime. Unlike `pos`,
+    /// this is never reset, so `tell()` stays meaningful across compaction.
+    consumed: usize,
+}
+
+#[pymethods]
+impl Unpacker {
+    #[new]
+    #[pyo3(signature = (data=None))]
+    fn new(data: Option<&[u8]>) -> Self {
+        let mut unpacker = Self {
+            buffer: Vec::new(),
+            pos: 0,
+            consumed: 0,
+        };
+        if let Some(data) = data {
+            unpacker.feed(data);
+        }
+        unpacker
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Total number of bytes consumed by completed objects so far.
+    fn tell(&self) -> usize {
+        self.consumed
+    }
+
+    /// Number of buffered bytes not yet consumed by a complete object.
+    fn bytes_remaining(&self) -> usize {
+        self.buffer.len() - self.pos
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        if slf.pos >= slf.buffer.len() {
+            slf.compact();
+            return Ok(None);
+        }
+        let mut cursor = Cursor::new(&slf.buffer[slf.pos..]);
+        let opts = decode::DecodeOptions::new(None, true);
+        match decode::read_object(py, &mut cursor, &opts) {
+            Ok(obj) => {
+                let consumed = cursor.position() as usize;
+                slf.pos += consumed;
+                slf.consumed += consumed;
+                Ok(Some(obj))
+            }
+            // The cursor only ever runs out of bytes, never hits real I/O,
+            // so an IO error here means the buffered tail is a truncated
+            // object rather than a decode failure - wait for more data.
+            Err(e) if e.is_instance_of::<PyIOError>(py) => {
+                slf.compact();
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Unpacker {
+    /// Drops the already-consumed prefix. Only called when iteration is
+    /// about to pause (buffer exhausted or next object incomplete), not
+    /// after every yielded object, so decoding N objects out of one fed
+    /// chunk doesn't cost an O(N) memmove per object.
+    fn compact(&mut self) {
+        if self.pos > 0 {
+            self.buffer.drain(..self.pos);
+            self.pos = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::write_object;
+
+    fn encode_int(n: i64) -> Vec<u8> {
+        Python::with_gil(|py| {
+            let mut buf = Vec::new();
+            let value = n.into_pyobject(py).unwrap().into_any();
+            write_object(&mut buf, &value, None).unwrap();
+            buf
+        })
+    }
+
+    fn next_value(py: Python<'_>, unpacker: &Bound<'_, Unpacker>) -> Option<i64> {
+        let obj = Unpacker::__next__(unpacker.borrow_mut(), py).unwrap()?;
+        Some(obj.extract::<i64>(py).unwrap())
+    }
+
+    #[test]
+    fn iterates_concatenated_messages() {
+        Python::with_gil(|py| {
+            let mut data = encode_int(1);
+            data.extend(encode_int(2));
+            data.extend(encode_int(3));
+
+            let unpacker = Bound::new(py, Unpacker::new(Some(&data))).unwrap();
+            assert_eq!(next_value(py, &unpacker), Some(1));
+            assert_eq!(next_value(py, &unpacker), Some(2));
+            assert_eq!(next_value(py, &unpacker), Some(3));
+            assert_eq!(next_value(py, &unpacker), None);
+        });
+    }
+
+    #[test]
+    fn pauses_on_truncated_message_and_resumes_after_feed() {
+        Python::with_gil(|py| {
+            let full = encode_int(12345);
+            let (head, tail) = full.split_at(full.len() - 1);
+
+            let unpacker = Bound::new(py, Unpacker::new(Some(head))).unwrap();
+            assert_eq!(next_value(py, &unpacker), None);
+            assert_eq!(unpacker.borrow().bytes_remaining(), head.len());
+
+            unpacker.borrow_mut().feed(tail);
+            assert_eq!(next_value(py, &unpacker), Some(12345));
+        });
+    }
+
+    #[test]
+    fn tell_tracks_total_consumed_across_compaction() {
+        Python::with_gil(|py| {
+            let first = encode_int(1);
+            let second = encode_int(2);
+            let mut data = first.clone();
+            data.extend(second.clone());
+
+            let unpacker = Bound::new(py, Unpacker::new(Some(&data))).unwrap();
+            assert_eq!(next_value(py, &unpacker), Some(1));
+            assert_eq!(unpacker.borrow().tell(), first.len());
+            assert_eq!(next_value(py, &unpacker), Some(2));
+            assert_eq!(unpacker.borrow().tell(), first.len() + second.len());
+        });
+    }
+}